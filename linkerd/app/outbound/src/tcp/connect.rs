@@ -1,3 +1,4 @@
+use super::limit::{self, ConcurrencyLimit};
 use super::opaque_transport::OpaqueTransport;
 use crate::{target::Endpoint, Outbound};
 use futures::future;
@@ -37,6 +38,7 @@ impl<C> Outbound<C> {
     >
     where
         Endpoint<P>: svc::Param<Option<SessionProtocol>>,
+        Endpoint<P>: svc::Param<ConcurrencyLimit> + svc::Param<Remote<ServerAddr>>,
         C: svc::Service<Endpoint<P>, Error = io::Error> + Clone + Send + 'static,
         C::Response: tls::HasNegotiatedProtocol,
         C::Response: io::AsyncRead + io::AsyncWrite + Send + Unpin + 'static,
@@ -61,6 +63,10 @@ impl<C> Outbound<C> {
             // Limits the time we wait for a connection to be established.
             .push_timeout(config.proxy.connect.timeout)
             .push(svc::stack::BoxFuture::layer())
+            // Bounds the number of connections dialed to an endpoint
+            // concurrently, shedding load instead of queuing when the
+            // endpoint is so configured.
+            .push(limit::layer())
             .push(rt.metrics.transport.layer_connect())
             .push_map_target(move |e: Endpoint<P>| {
                 if identity_disabled {
@@ -0,0 +1,175 @@
+use futures::future;
+use http;
+use linkerd_app_core::{
+    svc,
+    transport::{Remote, ServerAddr},
+    Error,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+use tracing::trace;
+
+/// The maximum number of connections that may be dialed to an endpoint
+/// concurrently, and how the endpoint behaves once that limit is reached.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ConcurrencyLimit {
+    /// The maximum number of in-flight connection attempts.
+    pub limit: usize,
+    /// When `true`, connection attempts issued while the limit is already
+    /// reached fail immediately with `Overloaded` rather than queuing for a
+    /// permit.
+    pub load_shed: bool,
+}
+
+/// Indicates that an endpoint's concurrency limit was reached and the
+/// endpoint is configured to load-shed rather than queue.
+///
+/// This stack operates below HTTP (it bounds raw TCP dials), so it can't
+/// produce an `http::Response` itself. [`respond`] downcasts an `Error` to
+/// this type and builds the `503 Service Unavailable` that should be
+/// returned for it; whatever HTTP-serving stack sits above this one is
+/// responsible for calling it once the error has propagated that far.
+#[derive(Debug, Default)]
+pub struct Overloaded(());
+
+impl std::fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("endpoint concurrency limit reached")
+    }
+}
+
+impl std::error::Error for Overloaded {}
+
+/// Translates an `Overloaded` error into the `503 Service Unavailable` it
+/// should produce at the HTTP boundary. Returns `None` for any other error,
+/// so this can be chained with the other typed-error rescues an HTTP-serving
+/// stack applies when finalizing a response.
+pub fn respond<B: Default>(error: &Error) -> Option<http::Response<B>> {
+    error.downcast_ref::<Overloaded>()?;
+    let mut res = http::Response::new(B::default());
+    *res.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+    Some(res)
+}
+
+pub fn layer() -> Layer {
+    Layer
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Layer;
+
+impl<S> tower::layer::Layer<S> for Layer {
+    type Service = ConcurrencyLimited<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConcurrencyLimited::new(inner)
+    }
+}
+
+/// How long a per-endpoint semaphore may sit unused (no permits checked out)
+/// before it's evicted from the map.
+///
+/// Outbound endpoints churn continuously as Kubernetes pods come and go, so
+/// without eviction this map would grow for the lifetime of the process.
+const IDLE_TTL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    semaphore: Arc<Semaphore>,
+    last_used: Instant,
+}
+
+/// Bounds the number of in-flight connection attempts to each endpoint,
+/// keyed by the endpoint's address, as configured by the target's
+/// `ConcurrencyLimit`.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimited<S> {
+    inner: S,
+    semaphores: Arc<Mutex<HashMap<SocketAddr, Entry>>>,
+}
+
+impl<S> ConcurrencyLimited<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            semaphores: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn semaphore_for(&self, addr: SocketAddr, limit: usize) -> Arc<Semaphore> {
+        let now = Instant::now();
+        let mut semaphores = self.semaphores.lock().unwrap();
+
+        // Evict entries that have had no outstanding permits for a while.
+        // `strong_count() == 1` means only this map holds a reference, i.e.
+        // there's no connection attempt (or another lookup) currently using
+        // it.
+        semaphores.retain(|_, entry| {
+            Arc::strong_count(&entry.semaphore) > 1 || now.saturating_duration_since(entry.last_used) < IDLE_TTL
+        });
+
+        let entry = semaphores.entry(addr).or_insert_with(|| Entry {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            last_used: now,
+        });
+        entry.last_used = now;
+        entry.semaphore.clone()
+    }
+}
+
+impl<T, S> svc::Service<T> for ConcurrencyLimited<S>
+where
+    T: svc::Param<ConcurrencyLimit> + svc::Param<Remote<ServerAddr>>,
+    S: svc::Service<T, Error = Error> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Response: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, Error>> + Send>>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, ep: T) -> Self::Future {
+        let ConcurrencyLimit { limit, load_shed } = ep.param();
+        let Remote(ServerAddr(addr)) = ep.param();
+        let semaphore = self.semaphore_for(addr, limit);
+
+        if load_shed {
+            let permit = match semaphore.try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    trace!(%addr, limit, "endpoint concurrency limit reached; shedding load");
+                    return Box::pin(future::err(Overloaded(()).into()));
+                }
+            };
+            let mut inner = self.inner.clone();
+            return Box::pin(async move {
+                let _permit = permit;
+                inner.call(ep).await
+            });
+        }
+
+        // Acquire the permit *before* dialing, so the configured limit
+        // actually bounds the number of connection attempts in flight
+        // rather than just how long callers wait on them.
+        let mut inner = self.inner.clone();
+        Box::pin(async move {
+            let _permit = semaphore
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
+            inner.call(ep).await
+        })
+    }
+}
@@ -0,0 +1,161 @@
+//! A `Classify` implementation for gRPC traffic.
+//!
+//! gRPC conveys the outcome of a call via the `grpc-status` trailer (or, for
+//! trailers-only responses, a response header of the same name) rather than
+//! the HTTP status line, so a generic HTTP classifier can't tell a
+//! successful gRPC call from a failed one. `GrpcClassify` reads that status
+//! and sorts it into a configurable set of success/failure codes.
+
+use crate::metrics::classify::{Classify, ClassifyEos, ClassifyResponse};
+use crate::timeout::ProxyTimedOut;
+use http::HeaderMap;
+use linkerd2_error::Error;
+use std::sync::Arc;
+
+/// A `grpc-status` code, as defined by the gRPC wire protocol.
+pub type Code = u32;
+
+pub const OK: Code = 0;
+pub const CANCELLED: Code = 1;
+pub const UNKNOWN: Code = 2;
+pub const INVALID_ARGUMENT: Code = 3;
+pub const DEADLINE_EXCEEDED: Code = 4;
+pub const NOT_FOUND: Code = 5;
+pub const ALREADY_EXISTS: Code = 6;
+pub const PERMISSION_DENIED: Code = 7;
+pub const RESOURCE_EXHAUSTED: Code = 8;
+pub const FAILED_PRECONDITION: Code = 9;
+pub const ABORTED: Code = 10;
+pub const OUT_OF_RANGE: Code = 11;
+pub const UNIMPLEMENTED: Code = 12;
+pub const INTERNAL: Code = 13;
+pub const UNAVAILABLE: Code = 14;
+pub const DATA_LOSS: Code = 15;
+pub const UNAUTHENTICATED: Code = 16;
+
+/// The `grpc-status` codes treated as successes when a target doesn't
+/// configure its own set.
+const DEFAULT_SUCCESS_CODES: &[Code] = &[OK, NOT_FOUND, ALREADY_EXISTS, INVALID_ARGUMENT];
+
+/// The result of classifying a gRPC call.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Class {
+    Success(Code),
+    Failure(Code),
+    /// The call failed with an error before a `grpc-status` was received,
+    /// e.g. because the connection was reset. A timeout is classified as
+    /// `Failure(DEADLINE_EXCEEDED)` instead, and a stream that ends without a
+    /// `grpc-status` as `Failure(UNKNOWN)`.
+    Error(String),
+}
+
+/// Classifies gRPC responses by their `grpc-status` code.
+#[derive(Clone, Debug)]
+pub struct GrpcClassify {
+    success_codes: Arc<[Code]>,
+}
+
+impl GrpcClassify {
+    pub fn new(success_codes: impl Into<Arc<[Code]>>) -> Self {
+        Self {
+            success_codes: success_codes.into(),
+        }
+    }
+
+    fn classify_code(&self, code: Code) -> Class {
+        if self.success_codes.contains(&code) {
+            Class::Success(code)
+        } else {
+            Class::Failure(code)
+        }
+    }
+
+    fn classify_error(&self, error: &Error) -> Class {
+        if error.is::<linkerd2_timeout::error::Timedout>() {
+            return Class::Failure(DEADLINE_EXCEEDED);
+        }
+
+        Class::Error(error.to_string())
+    }
+}
+
+impl Default for GrpcClassify {
+    fn default() -> Self {
+        Self::new(DEFAULT_SUCCESS_CODES.to_vec())
+    }
+}
+
+impl Classify for GrpcClassify {
+    type Class = Class;
+    type ClassifyEos = GrpcClassifyEos;
+    type ClassifyResponse = GrpcClassifyResponse;
+
+    fn classify<B>(&self, _req: &http::Request<B>) -> Self::ClassifyResponse {
+        GrpcClassifyResponse {
+            classify: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GrpcClassifyResponse {
+    classify: GrpcClassify,
+}
+
+impl ClassifyResponse for GrpcClassifyResponse {
+    type Class = Class;
+    type ClassifyEos = GrpcClassifyEos;
+
+    fn start<B>(self, rsp: &http::Response<B>) -> Self::ClassifyEos {
+        // A request that was timed out by the proxy itself is always a
+        // failure, whether or not the synthesized response happens to carry
+        // a `grpc-status` header of its own.
+        if rsp.extensions().get::<ProxyTimedOut>().is_some() {
+            return GrpcClassifyEos::Done(Class::Failure(DEADLINE_EXCEEDED));
+        }
+
+        // Trailers-only responses (e.g. an immediate failure with no
+        // message body) carry `grpc-status` in the response headers; for
+        // everything else, the class won't be known until end-of-stream.
+        match grpc_status(rsp.headers()) {
+            Some(code) => GrpcClassifyEos::Done(self.classify.classify_code(code)),
+            None => GrpcClassifyEos::Pending(self.classify),
+        }
+    }
+
+    fn error(self, error: &Error) -> Self::Class {
+        self.classify.classify_error(error)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum GrpcClassifyEos {
+    Pending(GrpcClassify),
+    Done(Class),
+}
+
+impl ClassifyEos for GrpcClassifyEos {
+    type Class = Class;
+
+    fn eos(self, trailers: Option<&HeaderMap>) -> Self::Class {
+        match self {
+            GrpcClassifyEos::Done(class) => class,
+            GrpcClassifyEos::Pending(classify) => {
+                let code = trailers.and_then(grpc_status).unwrap_or(UNKNOWN);
+                classify.classify_code(code)
+            }
+        }
+    }
+
+    fn error(self, error: &Error) -> Self::Class {
+        match self {
+            GrpcClassifyEos::Done(class) => class,
+            GrpcClassifyEos::Pending(classify) => classify.classify_error(error),
+        }
+    }
+}
+
+/// Reads and parses the `grpc-status` header/trailer, if present.
+fn grpc_status(headers: &HeaderMap) -> Option<Code> {
+    headers.get("grpc-status")?.to_str().ok()?.parse().ok()
+}
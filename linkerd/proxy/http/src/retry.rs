@@ -0,0 +1,475 @@
+//! A `tower_retry::Policy` that replays idempotent requests whose
+//! classification indicates a retryable failure.
+//!
+//! Replays are bounded by a per-target `Budget`, tallied over a rolling
+//! window of recent traffic, so that a target which is failing consistently
+//! does not have its retry traffic amplified without limit.
+
+use bytes::Bytes;
+use futures::{try_ready, Future, Poll};
+use http;
+use linkerd2_error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::trace;
+
+/// Used for stack targets that can determine whether a failed request may be
+/// retried, and, if so, how the retry budget for that target is configured.
+pub trait CanRetry {
+    type Retry: Retry + Clone + Send + Sync + 'static;
+
+    fn can_retry(&self) -> Self::Retry;
+}
+
+/// Decides whether a completed request/response exchange should be retried.
+///
+/// Unlike the metrics `Classify`/`ClassifyEos` pair, a `Retry` classifies a
+/// response from its head alone: `Policy::retry` is given only the
+/// `http::Response`, not its body, since the body hasn't been (and may never
+/// be) driven to completion at that point. For gRPC, this means a
+/// trailers-only failure is classified correctly, but a failure reported only
+/// in trailers after a `200 OK` head is not retried -- the original response
+/// is returned to the caller in that case.
+pub trait Retry {
+    type Class;
+
+    /// Classifies a response by its head.
+    fn classify_headers<B>(&self, rsp: &http::Response<B>) -> Self::Class;
+
+    /// Returns true if a response classified as `class` warrants a retry.
+    fn is_retryable(&self, class: &Self::Class) -> bool;
+
+    /// Classifies a transport/timeout failure (as opposed to a completed,
+    /// classified response) so that `is_retryable` can decide whether it
+    /// warrants a retry, the same as it would for a response class.
+    fn classify_error(&self, error: &Error) -> Self::Class;
+
+    /// The retry budget for this target.
+    fn budget(&self) -> Budget;
+}
+
+/// A buffered, replayable request body.
+///
+/// Retrying requires the original body to be replayed against a fresh
+/// connection, so only requests whose body is empty or has already been
+/// fully buffered into memory are eligible. `None` indicates a body that
+/// cannot be replayed (streaming, or larger than the buffer limit).
+#[derive(Clone, Debug, Default)]
+pub struct ReplayBody(Option<Bytes>);
+
+impl ReplayBody {
+    /// The largest body, in bytes, that will be buffered for a retry.
+    ///
+    /// Bodies larger than this are treated as unbufferable, since holding
+    /// them in memory for the lifetime of the request defeats the purpose of
+    /// streaming.
+    const MAX_BUFFERED_BYTES: usize = 64 * 1024;
+
+    pub fn empty() -> Self {
+        ReplayBody(Some(Bytes::new()))
+    }
+
+    pub fn buffer(bytes: Bytes) -> Self {
+        if bytes.len() > Self::MAX_BUFFERED_BYTES {
+            return ReplayBody(None);
+        }
+        ReplayBody(Some(bytes))
+    }
+
+    fn is_replayable(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+/// Builds a [`Replay`] retry middleware for targets that implement
+/// `CanRetry`, to be `.push()`ed above the `classify::Proxy` layer.
+pub fn layer() -> Layer {
+    Layer(())
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer(());
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+}
+
+pub struct MakeFuture<R, F> {
+    retry: Option<R>,
+    inner: F,
+}
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack { inner }
+    }
+}
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    T: CanRetry,
+    M: tower::Service<T>,
+{
+    type Response = Replay<T::Retry, M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<T::Retry, M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let retry = Some(target.can_retry());
+        let inner = self.inner.call(target);
+        MakeFuture { retry, inner }
+    }
+}
+
+impl<R, F> Future for MakeFuture<R, F>
+where
+    R: Retry + Clone,
+    F: Future,
+{
+    type Item = Replay<R, F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let retry = self.retry.take().expect("polled more than once");
+        let budget = retry.budget();
+        let policy = ReplayPolicy::new(retry, budget.clone());
+        Ok(Replay {
+            budget,
+            retry: tower::retry::Retry::new(policy, inner),
+        }
+        .into())
+    }
+}
+
+/// The `Service` built by the [`layer`] above: a `tower_retry::Retry` that
+/// deposits one unit into the target's retry `Budget` for every original
+/// (i.e. caller-issued, as opposed to replayed) request.
+#[derive(Clone, Debug)]
+pub struct Replay<R, S> {
+    budget: Budget,
+    retry: tower::retry::Retry<ReplayPolicy<R>, S>,
+}
+
+impl<R, S, Req> tower::Service<Req> for Replay<R, S>
+where
+    R: Retry + Clone,
+    tower::retry::Retry<ReplayPolicy<R>, S>: tower::Service<Req>,
+{
+    type Response = <tower::retry::Retry<ReplayPolicy<R>, S> as tower::Service<Req>>::Response;
+    type Error = <tower::retry::Retry<ReplayPolicy<R>, S> as tower::Service<Req>>::Error;
+    type Future = <tower::retry::Retry<ReplayPolicy<R>, S> as tower::Service<Req>>::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.retry.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        // Every call through this `Service` is an original request, issued
+        // by the caller rather than replayed by the `Policy` below -- so
+        // this is exactly where the budget's denominator should grow.
+        self.budget.deposit();
+        self.retry.call(req)
+    }
+}
+
+/// A `tower_retry::Policy` that replays requests whose response (or stream
+/// end) was classified as retryable, subject to the target's retry `Budget`.
+#[derive(Clone, Debug)]
+pub struct ReplayPolicy<R> {
+    retry: R,
+    budget: Budget,
+}
+
+impl<R> ReplayPolicy<R> {
+    pub fn new(retry: R, budget: Budget) -> Self {
+        Self { retry, budget }
+    }
+}
+
+impl<R, A, B, C> tower::retry::Policy<http::Request<ReplayBody>, http::Response<B>, Error>
+    for ReplayPolicy<R>
+where
+    R: Retry<Class = C> + Clone,
+    C: Send + 'static,
+{
+    type Future = futures::future::FutureResult<Self, Error>;
+
+    fn retry(
+        &self,
+        req: &http::Request<ReplayBody>,
+        result: Result<&http::Response<B>, &Error>,
+    ) -> Option<Self::Future> {
+        if !req.body().is_replayable() {
+            trace!("request body is not replayable; not retrying");
+            return None;
+        }
+
+        let retryable = match result {
+            Ok(rsp) => {
+                let class = self.retry.classify_headers(rsp);
+                self.retry.is_retryable(&class)
+            }
+            Err(error) => {
+                // Let the target's policy decide whether this kind of
+                // failure warrants a retry, exactly as it would for a
+                // classified response -- a reset after partial processing,
+                // for instance, may not be safe to replay even though the
+                // budget has room for it.
+                let class = self.retry.classify_error(error);
+                self.retry.is_retryable(&class)
+            }
+        };
+
+        if !retryable {
+            return None;
+        }
+
+        if !self.budget.withdraw() {
+            trace!("retry budget exhausted; returning original response");
+            return None;
+        }
+
+        Some(futures::future::ok(self.clone()))
+    }
+
+    fn clone_request(&self, req: &http::Request<ReplayBody>) -> Option<http::Request<ReplayBody>> {
+        if !req.body().is_replayable() {
+            return None;
+        }
+
+        let mut clone = http::Request::new(req.body().clone());
+        *clone.method_mut() = req.method().clone();
+        *clone.uri_mut() = req.uri().clone();
+        *clone.headers_mut() = req.headers().clone();
+        *clone.version_mut() = req.version();
+        Some(clone)
+    }
+}
+
+/// A budget that bounds the rate of retries relative to the volume of
+/// original requests for a single target, over a rolling time window.
+///
+/// `deposit` earns `retry_ratio` tokens for every original request; `withdraw`
+/// spends one token per retry attempt and fails once the window's earned
+/// total would be exceeded -- at that point the original, non-retried
+/// response is returned to the caller. Earned and spent tokens are tracked
+/// per-second over the trailing [`WINDOW_SECONDS`], so a target that was
+/// failing (and so exhausted its budget) an hour ago isn't penalized
+/// forever -- unlike an unbounded balance, this one decays as old traffic
+/// ages out of the window. `min_per_second` additionally grants a traffic
+/// -independent floor, scaled by how much of the window has elapsed, so a
+/// low-volume target can still retry a handful of times without much
+/// original traffic to amortize over.
+#[derive(Clone, Debug)]
+pub struct Budget(Arc<Inner>);
+
+/// The width, in seconds, of the rolling window over which deposits and
+/// withdrawals are tallied.
+const WINDOW_SECONDS: usize = 10;
+
+#[derive(Debug)]
+struct Inner {
+    retry_ratio: f64,
+    min_per_second: u32,
+    started: Instant,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// A ring of per-second deposit/withdrawal counts; `current` is the slot
+    /// for `current_second`, and the `WINDOW_SECONDS - 1` slots before it
+    /// hold the rest of the window.
+    slots: [Slot; WINDOW_SECONDS],
+    current: usize,
+    current_second: u64,
+    /// Running totals across every slot in the window, kept in sync with
+    /// `slots` so `try_withdraw` doesn't need to re-sum the ring every call.
+    deposits: u64,
+    withdrawals: u64,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Slot {
+    deposits: u64,
+    withdrawals: u64,
+}
+
+impl State {
+    /// Rolls the window forward to `now_second`, evicting any slots that
+    /// have aged out and subtracting their counts from the running totals.
+    fn advance(&mut self, now_second: u64) {
+        if now_second <= self.current_second {
+            return;
+        }
+
+        let elapsed = now_second - self.current_second;
+        let to_clear = elapsed.min(WINDOW_SECONDS as u64);
+        for _ in 0..to_clear {
+            self.current = (self.current + 1) % WINDOW_SECONDS;
+            let stale = std::mem::take(&mut self.slots[self.current]);
+            self.deposits -= stale.deposits;
+            self.withdrawals -= stale.withdrawals;
+        }
+        self.current_second = now_second;
+    }
+
+    fn deposit(&mut self, now_second: u64) {
+        self.advance(now_second);
+        self.slots[self.current].deposits += 1;
+        self.deposits += 1;
+    }
+
+    fn try_withdraw(
+        &mut self,
+        now_second: u64,
+        retry_ratio: f64,
+        min_per_second: u32,
+        active_seconds: u64,
+    ) -> bool {
+        self.advance(now_second);
+
+        let earned = self.deposits as f64 * retry_ratio + min_per_second as f64 * active_seconds as f64;
+        if earned - self.withdrawals as f64 < 1.0 {
+            return false;
+        }
+
+        self.slots[self.current].withdrawals += 1;
+        self.withdrawals += 1;
+        true
+    }
+}
+
+impl Budget {
+    pub fn new(min_per_second: u32, retry_ratio: f32) -> Self {
+        assert!(retry_ratio >= 0.0, "retry_ratio must not be negative");
+        Self(Arc::new(Inner {
+            retry_ratio: retry_ratio as f64,
+            min_per_second,
+            started: Instant::now(),
+            state: Mutex::new(State {
+                slots: [Slot::default(); WINDOW_SECONDS],
+                current: 0,
+                current_second: 0,
+                deposits: 0,
+                withdrawals: 0,
+            }),
+        }))
+    }
+
+    /// Records that an original (non-retried) request was issued, earning
+    /// this target's bucket `retry_ratio` tokens.
+    pub fn deposit(&self) {
+        self.deposit_at(Instant::now());
+    }
+
+    /// Attempts to withdraw the cost of a single retry from the bucket.
+    ///
+    /// Returns `false`, without modifying the bucket, if doing so would
+    /// exceed what's been earned over the trailing window.
+    pub fn withdraw(&self) -> bool {
+        self.withdraw_at(Instant::now())
+    }
+
+    fn elapsed_seconds(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.0.started).as_secs()
+    }
+
+    fn deposit_at(&self, now: Instant) {
+        let second = self.elapsed_seconds(now);
+        self.0.state.lock().unwrap().deposit(second);
+    }
+
+    fn withdraw_at(&self, now: Instant) -> bool {
+        let second = self.elapsed_seconds(now);
+        // The floor scales in with the window as it fills, rather than
+        // being granted all at once, so it still behaves like a per-second
+        // rate rather than an instantaneous windfall.
+        let active_seconds = (second + 1).min(WINDOW_SECONDS as u64);
+        self.0.state.lock().unwrap().try_withdraw(
+            second,
+            self.0.retry_ratio,
+            self.0.min_per_second,
+            active_seconds,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn withdraw_fails_on_empty_budget() {
+        let budget = Budget::new(0, 1.0);
+        assert!(!budget.withdraw());
+    }
+
+    #[test]
+    fn deposit_earns_one_withdraw_per_ratio_unit() {
+        let budget = Budget::new(0, 1.0);
+        budget.deposit();
+        assert!(budget.withdraw(), "first withdraw should succeed");
+        assert!(!budget.withdraw(), "second withdraw should exhaust the budget");
+    }
+
+    #[test]
+    fn fractional_ratio_requires_multiple_deposits() {
+        let budget = Budget::new(0, 0.5);
+        budget.deposit();
+        assert!(
+            !budget.withdraw(),
+            "half a token isn't enough for a full withdrawal"
+        );
+
+        budget.deposit();
+        assert!(budget.withdraw(), "two half-deposits earn one withdrawal");
+        assert!(!budget.withdraw());
+    }
+
+    #[test]
+    fn withdrawals_do_not_go_negative() {
+        let budget = Budget::new(0, 1.0);
+        budget.deposit();
+        assert!(budget.withdraw());
+        for _ in 0..8 {
+            assert!(!budget.withdraw());
+        }
+    }
+
+    #[test]
+    fn min_per_second_grants_a_floor_without_deposits() {
+        let budget = Budget::new(5, 0.0);
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert!(budget.withdraw_at(now));
+        }
+        assert!(
+            !budget.withdraw_at(now),
+            "floor for this second is exhausted"
+        );
+    }
+
+    #[test]
+    fn min_per_second_floor_grows_as_the_window_advances() {
+        let budget = Budget::new(5, 0.0);
+        let now = Instant::now();
+        assert!(budget.withdraw_at(now));
+
+        // A second later, the floor has grown by another `min_per_second`
+        // tokens, on top of the one already spent in the first second.
+        let later = now + Duration::from_secs(1);
+        for _ in 0..9 {
+            assert!(budget.withdraw_at(later));
+        }
+        assert!(!budget.withdraw_at(later));
+    }
+}
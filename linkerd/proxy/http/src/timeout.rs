@@ -1,4 +1,5 @@
 use futures::{try_ready, Future, Poll};
+use http::header::HeaderValue;
 use linkerd2_error::Error;
 use linkerd2_stack::Proxy;
 use linkerd2_timeout::{error, Timeout};
@@ -17,7 +18,10 @@ pub trait HasTimeout {
 /// specified for the target, a timeout is applied waiting for HTTP responses.
 ///
 /// Timeout errors are translated into `http::Response`s with appropiate
-/// status codes.
+/// status codes. Requests that carry a `grpc-timeout` header are additionally
+/// bound by that header's deadline (whichever of it and the target-configured
+/// timeout is shorter). Requests with a gRPC `content-type` have their expiry
+/// surfaced as a gRPC `DEADLINE_EXCEEDED` status rather than an HTTP `504`.
 pub fn layer() -> Layer {
     Layer
 }
@@ -36,13 +40,22 @@ pub struct MakeFuture<F> {
 }
 
 #[derive(Clone, Debug)]
-pub struct Service<S>(Timeout<S>);
+pub struct Service<S> {
+    inner: S,
+    timeout: Duration,
+}
 
 /// A marker set in `http::Response::extensions` that *this* process triggered
 /// the request timeout.
 #[derive(Debug)]
 pub struct ProxyTimedOut(());
 
+/// The `content-type` prefix used by gRPC requests and responses.
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+
+/// The header carrying a request-scoped gRPC deadline.
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
 impl<M> tower::layer::Layer<M> for Layer {
     type Service = Stack<M>;
 
@@ -80,7 +93,10 @@ impl<F: Future> Future for MakeFuture<F> {
         let inner = try_ready!(self.inner.poll());
 
         let svc = if let Some(timeout) = self.timeout {
-            tower::util::Either::A(Service(Timeout::new(inner, timeout)))
+            tower::util::Either::A(Service {
+                inner,
+                timeout,
+            })
         } else {
             tower::util::Either::B(inner)
         };
@@ -100,7 +116,13 @@ where
     type Future = ResponseFuture<P::Future, B>;
 
     fn proxy(&self, svc: &mut S, req: http::Request<A>) -> Self::Future {
-        ResponseFuture(self.0.proxy(svc, req), std::marker::PhantomData)
+        let (timeout, is_grpc) = effective_timeout(&req, self.timeout);
+        let inner = self.inner.proxy(svc, req);
+        ResponseFuture {
+            inner: Timeout::new(timer::Timeout::new(inner, timeout), timeout),
+            is_grpc,
+            _marker: std::marker::PhantomData,
+        }
     }
 }
 
@@ -115,18 +137,25 @@ where
     type Future = ResponseFuture<S::Future, B>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
-        self.0.poll_ready()
+        self.inner.poll_ready()
     }
 
     fn call(&mut self, req: http::Request<A>) -> Self::Future {
-        ResponseFuture(self.0.call(req), std::marker::PhantomData)
+        let (timeout, is_grpc) = effective_timeout(&req, self.timeout);
+        let inner = self.inner.call(req);
+        ResponseFuture {
+            inner: Timeout::new(timer::Timeout::new(inner, timeout), timeout),
+            is_grpc,
+            _marker: std::marker::PhantomData,
+        }
     }
 }
 
-pub struct ResponseFuture<F, B>(
-    Timeout<timer::Timeout<F>>,
-    std::marker::PhantomData<fn() -> B>,
-);
+pub struct ResponseFuture<F, B> {
+    inner: Timeout<timer::Timeout<F>>,
+    is_grpc: bool,
+    _marker: std::marker::PhantomData<fn() -> B>,
+}
 
 impl<F, B> Future for ResponseFuture<F, B>
 where
@@ -138,9 +167,12 @@ where
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.0.poll().or_else(|err| {
+        self.inner.poll().or_else(|err| {
             if let Some(err) = err.downcast_ref::<error::Timedout>() {
                 debug!("request timed out after {:?}", err.duration());
+                if self.is_grpc {
+                    return Ok(grpc_deadline_exceeded::<B>().into());
+                }
                 let mut res = http::Response::default();
                 *res.status_mut() = http::StatusCode::GATEWAY_TIMEOUT;
                 res.extensions_mut().insert(ProxyTimedOut(()));
@@ -157,4 +189,131 @@ where
             Err(err)
         })
     }
-}
\ No newline at end of file
+}
+
+/// Builds the gRPC-flavored deadline-exceeded response: a `200 OK` carrying
+/// `grpc-status`/`grpc-message` as response *headers* rather than trailers.
+///
+/// This is a "Trailers-Only" response in gRPC-over-HTTP/2 terms: since the
+/// proxy is failing the call before any message was ever produced, there's
+/// no data frame for the body to carry, and the gRPC spec permits the status
+/// to be conveyed in the initial (and only) HEADERS frame in that case. That
+/// lets this be built generically for any response body -- no data needs to
+/// flow through it -- rather than needing a body type that can carry
+/// trailers of its own.
+fn grpc_deadline_exceeded<B: Default>() -> http::Response<B> {
+    const GRPC_STATUS_DEADLINE_EXCEEDED: &str = "4";
+
+    let mut res = http::Response::new(B::default());
+    *res.status_mut() = http::StatusCode::OK;
+    res.headers_mut().insert(
+        "grpc-status",
+        http::HeaderValue::from_static(GRPC_STATUS_DEADLINE_EXCEEDED),
+    );
+    res.headers_mut().insert(
+        "grpc-message",
+        http::HeaderValue::from_static("request did not complete within the configured deadline"),
+    );
+    res.extensions_mut().insert(ProxyTimedOut(()));
+    res
+}
+
+/// Determines the duration to wait for a response to `req`, and whether
+/// `req` is a gRPC request (and so should have its expiry reported as a
+/// gRPC status rather than an HTTP one).
+///
+/// When `req` carries a valid `grpc-timeout` header, the returned duration is
+/// the minimum of that header's value and `configured`; otherwise
+/// `configured` is returned unchanged.
+fn effective_timeout<A>(req: &http::Request<A>, configured: Duration) -> (Duration, bool) {
+    let is_grpc = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with(GRPC_CONTENT_TYPE))
+        .unwrap_or(false);
+
+    let timeout = req
+        .headers()
+        .get(GRPC_TIMEOUT_HEADER)
+        .and_then(parse_grpc_timeout)
+        .map(|grpc| std::cmp::min(grpc, configured))
+        .unwrap_or(configured);
+
+    (timeout, is_grpc)
+}
+
+/// Parses a `grpc-timeout` header value.
+///
+/// Per the gRPC over HTTP/2 wire protocol, the value is an ASCII integer (at
+/// most 8 digits) immediately followed by a single-character unit: `H`
+/// (hours), `M` (minutes), `S` (seconds), `m` (milliseconds), `u`
+/// (microseconds), or `n` (nanoseconds). Returns `None` if the header isn't
+/// shaped like that, or if converting it to nanoseconds would overflow.
+fn parse_grpc_timeout(value: &HeaderValue) -> Option<Duration> {
+    let value = value.to_str().ok()?;
+    if value.is_empty() || value.len() > 9 {
+        return None;
+    }
+
+    let split = value.len() - 1;
+    let (digits, unit) = value.split_at(split);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+
+    let nanos_per_unit: u64 = match unit {
+        "H" => 60 * 60 * 1_000_000_000,
+        "M" => 60 * 1_000_000_000,
+        "S" => 1_000_000_000,
+        "m" => 1_000_000,
+        "u" => 1_000,
+        "n" => 1,
+        _ => return None,
+    };
+
+    let nanos = amount.checked_mul(nanos_per_unit)?;
+    Some(Duration::from_nanos(nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<Duration> {
+        parse_grpc_timeout(&HeaderValue::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn parses_each_unit() {
+        assert_eq!(parse("10H"), Some(Duration::from_secs(10 * 60 * 60)));
+        assert_eq!(parse("10M"), Some(Duration::from_secs(10 * 60)));
+        assert_eq!(parse("10S"), Some(Duration::from_secs(10)));
+        assert_eq!(parse("10m"), Some(Duration::from_millis(10)));
+        assert_eq!(parse("10u"), Some(Duration::from_micros(10)));
+        assert_eq!(parse("10n"), Some(Duration::from_nanos(10)));
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("S"), None);
+        assert_eq!(parse("10"), None);
+        assert_eq!(parse("-1S"), None);
+        assert_eq!(parse("10X"), None);
+        assert_eq!(parse("1 0S"), None);
+    }
+
+    #[test]
+    fn rejects_overflow() {
+        // 8 nines worth of hours overflows the nanosecond math.
+        assert_eq!(parse("99999999H"), None);
+    }
+
+    #[test]
+    fn rejects_too_many_digits() {
+        // The wire format allows at most 8 digits.
+        assert_eq!(parse("123456789S"), None);
+    }
+}
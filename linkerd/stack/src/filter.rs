@@ -1,7 +1,14 @@
 //! A `Service` middleware that applies arbitrary-user provided logic to each
 //! target before it is issued to an inner service.
 
-pub use tower::filter::{Filter, FilterLayer, Predicate};
+pub use tower::filter::{AsyncPredicate, Filter, FilterLayer, Predicate};
+
+use linkerd2_error::Error;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
 impl<T, P, S> super::NewService<T> for Filter<S, P>
 where
@@ -16,3 +23,104 @@ where
             .unwrap_or_else(super::ResultService::err)
     }
 }
+
+/// A `NewService` that admits or rejects a target by resolving an
+/// [`AsyncPredicate`]'s future, rather than deciding synchronously.
+///
+/// This allows admission to depend on I/O-bound decisions -- e.g.
+/// consulting an authorization/policy cache or a discovery lookup -- before
+/// the inner `NewService` is built. The synchronous `Predicate`/`Filter`
+/// pair above remains the zero-overhead path for checks that don't need to
+/// await anything.
+#[derive(Clone, Debug)]
+pub struct AsyncFilter<S, P> {
+    inner: S,
+    predicate: P,
+}
+
+impl<S, P> AsyncFilter<S, P> {
+    pub fn new(inner: S, predicate: P) -> Self {
+        Self { inner, predicate }
+    }
+}
+
+impl<T, P, S> super::NewService<T> for AsyncFilter<S, P>
+where
+    P: AsyncPredicate<T> + Clone,
+    S: super::NewService<P::Request> + Clone,
+{
+    type Service = Admitting<P::Future, S, P::Request>;
+
+    fn new_service(&mut self, target: T) -> Self::Service {
+        let checking = self.predicate.clone().check(target);
+        Admitting::new(checking, self.inner.clone())
+    }
+}
+
+/// The `Service` returned by [`AsyncFilter::new_service`].
+///
+/// Calls are held pending until the predicate's future resolves; once it
+/// does, every subsequent call is dispatched directly to the service built
+/// from the admitted request -- or fails with the rejection error -- without
+/// re-checking the predicate.
+pub struct Admitting<F, S, Req>
+where
+    S: super::NewService<Req>,
+{
+    state: State<F, S, Req>,
+}
+
+enum State<F, S, Req>
+where
+    S: super::NewService<Req>,
+{
+    Checking(Pin<Box<F>>, Option<S>),
+    Ready(super::ResultService<S::Service>),
+}
+
+impl<F, S, Req> Admitting<F, S, Req>
+where
+    S: super::NewService<Req>,
+{
+    fn new(checking: F, new_service: S) -> Self {
+        Self {
+            state: State::Checking(Box::pin(checking), Some(new_service)),
+        }
+    }
+}
+
+impl<F, S, Req, Req2> tower::Service<Req2> for Admitting<F, S, Req>
+where
+    F: Future<Output = Result<Req, Error>>,
+    S: super::NewService<Req>,
+    S::Service: tower::Service<Req2, Error = Error>,
+{
+    type Response = <S::Service as tower::Service<Req2>>::Response;
+    type Error = Error;
+    type Future = <super::ResultService<S::Service> as tower::Service<Req2>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            self.state = match &mut self.state {
+                State::Ready(svc) => return svc.poll_ready(cx),
+                State::Checking(future, new_service) => match future.as_mut().poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok(req)) => {
+                        let mut new_service =
+                            new_service.take().expect("must not poll after ready");
+                        let svc = new_service.new_service(req);
+                        State::Ready(super::ResultService::ok(svc))
+                    }
+                    Poll::Ready(Err(e)) => State::Ready(super::ResultService::err(e)),
+                },
+            };
+        }
+    }
+
+    fn call(&mut self, req: Req2) -> Self::Future {
+        match &mut self.state {
+            State::Ready(svc) => svc.call(req),
+            State::Checking(..) => panic!("poll_ready must be called before call"),
+        }
+    }
+}